@@ -0,0 +1,77 @@
+use crate::types::client::Error;
+use crate::types::jsonrpc::{self, JsonValue};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+/// Places each entry of a batch response into the slot matching its submission position,
+/// using `id_to_pos` to recover that position regardless of the order the server replied in.
+///
+/// A slot whose ID never shows up in `rps` is reported as an error rather than left out, so
+/// the returned `Vec` always has exactly `len` entries, aligned with the submitted batch.
+///
+/// Shared by [`HttpClient`](crate::client::http::client::HttpClient) and
+/// [`IpcClient`](crate::client::ipc::client::IpcClient) so a fix to the reassembly logic reaches
+/// both transports at once.
+pub(crate) fn reassemble_batch_results(
+	rps: Vec<jsonrpc::Output>,
+	mut ids: HashSet<u64>,
+	id_to_pos: &HashMap<u64, usize>,
+	len: usize,
+) -> Result<Vec<Result<JsonValue, Error>>, Error> {
+	let mut responses: Vec<Option<Result<JsonValue, Error>>> = (0..len).map(|_| None).collect();
+	for rp in rps {
+		let id = match rp.id().as_number() {
+			Some(n) => *n,
+			_ => return Err(Error::InvalidRequestId),
+		};
+		if !ids.remove(&id) {
+			return Err(Error::InvalidRequestId);
+		}
+		let pos = id_to_pos[&id];
+		responses[pos] = Some(rp.try_into().map_err(Error::Request));
+	}
+	// Any slot still `None` means the server never replied to that call.
+	let responses = responses
+		.into_iter()
+		.map(|rp| rp.unwrap_or_else(|| Err(Error::Custom("Server did not reply to every call in the batch".to_string()))))
+		.collect();
+	Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn output(id: u64, result: u64) -> jsonrpc::Output {
+		serde_json::from_str(&format!(r#"{{"jsonrpc":"2.0","result":{},"id":{}}}"#, result, id)).unwrap()
+	}
+
+	#[test]
+	fn reorders_responses_back_to_submission_order() {
+		// Calls were submitted in the order 10, 11, 12 (positions 0, 1, 2), but the server
+		// replied out of order.
+		let id_to_pos = [(10, 0), (11, 1), (12, 2)].into_iter().collect();
+		let ids = [10, 11, 12].into_iter().collect();
+		let rps = vec![output(12, 102), output(10, 100), output(11, 101)];
+
+		let results = reassemble_batch_results(rps, ids, &id_to_pos, 3).unwrap();
+
+		assert_eq!(results[0].as_ref().unwrap(), &serde_json::json!(100));
+		assert_eq!(results[1].as_ref().unwrap(), &serde_json::json!(101));
+		assert_eq!(results[2].as_ref().unwrap(), &serde_json::json!(102));
+	}
+
+	#[test]
+	fn reports_an_error_for_a_call_the_server_never_answered() {
+		// Only two of the three submitted calls got a reply.
+		let id_to_pos = [(10, 0), (11, 1), (12, 2)].into_iter().collect();
+		let ids = [10, 11, 12].into_iter().collect();
+		let rps = vec![output(10, 100), output(12, 102)];
+
+		let results = reassemble_batch_results(rps, ids, &id_to_pos, 3).unwrap();
+
+		assert!(results[0].is_ok());
+		assert!(results[1].is_err());
+		assert!(results[2].is_ok());
+	}
+}
@@ -1,18 +1,34 @@
+use crate::client::batch::reassemble_batch_results;
+use crate::client::http::middleware::{Next, NotificationNext, RpcClientServiceT};
 use crate::client::http::transport::HttpTransportClient;
 use crate::types::client::Error;
 use crate::types::jsonrpc::{self, JsonValue};
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::Instrument;
 
 /// Default maximum request body size (10 MB).
 const DEFAULT_MAX_BODY_SIZE_TEN_MB: u32 = 10 * 1024 * 1024;
 
+/// Default maximum number of concurrent in-flight requests.
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 256;
+
+/// Default maximum number of characters of a request/response body included in a log event.
+const DEFAULT_MAX_LOG_BODY_LEN: usize = 4096;
+
 /// HTTP configuration.
 #[derive(Copy, Clone)]
 pub struct HttpConfig {
 	/// Maximum request body size in bytes.
 	pub max_request_body_size: u32,
+	/// Maximum number of requests (a batch counts as one) that may be in flight at once.
+	pub max_concurrent_requests: usize,
+	/// Maximum number of characters of a serialized request/response body that are included in
+	/// a log event; longer bodies are truncated.
+	pub max_log_body_len: usize,
 }
 
 /// JSON-RPC HTTP Client that provides functionality to perform method calls and notifications.
@@ -23,22 +39,69 @@ pub struct HttpClient {
 	transport: HttpTransportClient,
 	/// Request ID that wraps around when overflowing.
 	request_id: AtomicU64,
+	/// Limits how many requests may be in flight towards the transport at the same time.
+	request_limit: Semaphore,
+	/// Middleware stack invoked, in order, before the request reaches the transport.
+	middleware: Vec<Arc<dyn RpcClientServiceT>>,
+	/// Maximum number of characters of a body logged in a tracing event.
+	max_log_body_len: usize,
 }
 
 impl Default for HttpConfig {
 	fn default() -> Self {
-		Self { max_request_body_size: DEFAULT_MAX_BODY_SIZE_TEN_MB }
+		Self {
+			max_request_body_size: DEFAULT_MAX_BODY_SIZE_TEN_MB,
+			max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+			max_log_body_len: DEFAULT_MAX_LOG_BODY_LEN,
+		}
 	}
 }
 
 impl HttpClient {
 	/// Initializes a new HTTP client.
 	///
+	/// The client dispatches directly to the transport, with no middleware installed; use
+	/// [`with_middleware`](HttpClient::with_middleware) to install a stack of
+	/// [`RpcClientServiceT`] layers.
+	///
 	/// Fails when the URL is invalid.
 	pub fn new(target: impl AsRef<str>, config: HttpConfig) -> Result<Self, Error> {
 		let transport = HttpTransportClient::new(target, config.max_request_body_size)
 			.map_err(|e| Error::TransportError(Box::new(e)))?;
-		Ok(Self { transport, request_id: AtomicU64::new(0) })
+		Ok(Self {
+			transport,
+			request_id: AtomicU64::new(0),
+			request_limit: Semaphore::new(config.max_concurrent_requests),
+			middleware: Vec::new(),
+			max_log_body_len: config.max_log_body_len,
+		})
+	}
+
+	/// Installs a stack of [`RpcClientServiceT`] layers, invoked in order before the request
+	/// reaches the transport.
+	pub fn with_middleware(mut self, middleware: Vec<Arc<dyn RpcClientServiceT>>) -> Self {
+		self.middleware = middleware;
+		self
+	}
+
+	/// Logs a serializable request/response body as a `trace`-level event, truncated to
+	/// `max_log_body_len` characters so a large payload does not flood the logs.
+	///
+	/// Returns the byte size of the serialized body, so callers can also record it as a span
+	/// field.
+	fn log_body(&self, direction: &'static str, body: &impl serde::Serialize) -> usize {
+		let body = match serde_json::to_string(body) {
+			Ok(body) => body,
+			Err(_) => return 0,
+		};
+		let size = body.len();
+		if body.chars().count() <= self.max_log_body_len {
+			tracing::trace!(%direction, %body, size, "jsonrpc body");
+		} else {
+			let truncated: String = body.chars().take(self.max_log_body_len).collect();
+			tracing::trace!(%direction, body = %truncated, size, truncated = true, "jsonrpc body");
+		}
+		size
 	}
 
 	/// Send a notification to the server.
@@ -49,107 +112,243 @@ impl HttpClient {
 		method: impl Into<String>,
 		params: impl Into<jsonrpc::Params>,
 	) -> Result<(), Error> {
-		let request = jsonrpc::Request::Single(jsonrpc::Call::Notification(jsonrpc::Notification {
-			jsonrpc: jsonrpc::Version::V2,
-			method: method.into(),
-			params: params.into(),
-		}));
+		let method = method.into();
+		let span = tracing::debug_span!("notification", %method, size = tracing::field::Empty);
+		async move {
+			let request = jsonrpc::Request::Single(jsonrpc::Call::Notification(jsonrpc::Notification {
+				jsonrpc: jsonrpc::Version::V2,
+				method,
+				params: params.into(),
+			}));
 
-		self.transport.send_notification(request).await.map_err(|e| Error::TransportError(Box::new(e)))
+			let size = self.log_body("send", &request);
+			tracing::Span::current().record("size", &size);
+			self.next_notification().run(request).await
+		}
+		.instrument(span)
+		.await
 	}
 
 	/// Perform a request towards the server.
 	///
+	/// Waits for a free slot if `max_concurrent_requests` in-flight requests are already pending;
+	/// see [`try_request`](HttpClient::try_request) for a non-blocking variant.
+	///
 	/// WARNING: This method must be executed on [Tokio 0.2](https://docs.rs/tokio/0.2.22/tokio).
 	pub async fn request(
 		&self,
 		method: impl Into<String>,
 		params: impl Into<jsonrpc::Params>,
+	) -> Result<JsonValue, Error> {
+		let _permit = self.request_limit.acquire().await;
+		self.request_inner(method, params).await
+	}
+
+	/// Perform a request towards the server, failing immediately instead of waiting if
+	/// `max_concurrent_requests` in-flight requests are already pending.
+	///
+	/// WARNING: This method must be executed on [Tokio 0.2](https://docs.rs/tokio/0.2.22/tokio).
+	pub async fn try_request(
+		&self,
+		method: impl Into<String>,
+		params: impl Into<jsonrpc::Params>,
+	) -> Result<JsonValue, Error> {
+		let _permit = self
+			.request_limit
+			.try_acquire()
+			.map_err(|_| Error::Custom("No request slots available".to_string()))?;
+		self.request_inner(method, params).await
+	}
+
+	async fn request_inner(
+		&self,
+		method: impl Into<String>,
+		params: impl Into<jsonrpc::Params>,
 	) -> Result<JsonValue, Error> {
 		// NOTE: `fetch_add` wraps on overflow which is intended.
 		let id = self.request_id.fetch_add(1, Ordering::SeqCst);
-		let request = jsonrpc::Request::Single(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
-			jsonrpc: jsonrpc::Version::V2,
-			method: method.into(),
-			params: params.into(),
-			id: jsonrpc::Id::Num(id),
-		}));
-
-		let response = self
-			.transport
-			.send_request_and_wait_for_response(request)
-			.await
-			.map_err(|e| Error::TransportError(Box::new(e)))?;
+		let method = method.into();
+		let span = tracing::debug_span!("request", %method, id, size = tracing::field::Empty);
+		async move {
+			let request = jsonrpc::Request::Single(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
+				jsonrpc: jsonrpc::Version::V2,
+				method,
+				params: params.into(),
+				id: jsonrpc::Id::Num(id),
+			}));
 
-		match response {
-			jsonrpc::Response::Single(rp) => Self::process_response(rp, id),
-			// Server should not send batch response to a single request.
-			jsonrpc::Response::Batch(_rps) => {
-				Err(Error::Custom("Server replied with batch response to a single request".to_string()))
-			}
-			// Server should not reply to a Notification.
-			jsonrpc::Response::Notif(_notif) => {
-				Err(Error::Custom(format!("Server replied with notification response to request ID: {}", id)))
+			let size = self.log_body("send", &request);
+			tracing::Span::current().record("size", &size);
+			let response = self.next().run(request).await?;
+			self.log_body("recv", &response);
+
+			match response {
+				jsonrpc::Response::Single(rp) => Self::process_response(rp, id),
+				// Server should not send batch response to a single request.
+				jsonrpc::Response::Batch(_rps) => {
+					Err(Error::Custom("Server replied with batch response to a single request".to_string()))
+				}
+				// Server should not reply to a Notification.
+				jsonrpc::Response::Notif(_notif) => {
+					Err(Error::Custom(format!("Server replied with notification response to request ID: {}", id)))
+				}
 			}
 		}
+		.instrument(span)
+		.await
+	}
+
+	/// Builds a [`Next`] pointing at the front of the middleware stack.
+	fn next(&self) -> Next<'_> {
+		Next { transport: &self.transport, remaining: &self.middleware }
+	}
+
+	/// Builds a [`NotificationNext`] pointing at the front of the middleware stack.
+	fn next_notification(&self) -> NotificationNext<'_> {
+		NotificationNext { transport: &self.transport, remaining: &self.middleware }
 	}
 
 	/// Perform a batch request towards the server.
 	///
 	/// Returns `Ok` if all requests were answered successfully.
 	/// Returns `Error` if any of the requests fails.
+	///
+	/// The whole batch counts as a single request against `max_concurrent_requests`.
 	//
 	// TODO(niklasad1): maybe simplify generic `requests`, it's quite unreadable.
 	pub async fn batch_request<'a>(
 		&self,
 		requests: impl IntoIterator<Item = (impl Into<String>, impl Into<jsonrpc::Params>)>,
 	) -> Result<Vec<JsonValue>, Error> {
-		let mut calls = Vec::new();
-		// NOTE(niklasad1): If more than `u64::MAX` requests are performed in the `batch` then duplicate IDs are used
-		// which we don't support because ID is used to uniquely identify a given request.
-		let mut ids = HashSet::new();
-
-		for (method, params) in requests.into_iter() {
-			let id = self.request_id.fetch_add(1, Ordering::SeqCst);
-			calls.push(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
-				jsonrpc: jsonrpc::Version::V2,
-				method: method.into(),
-				params: params.into(),
-				id: jsonrpc::Id::Num(id),
-			}));
-			ids.insert(id);
-		}
+		let _permit = self.request_limit.acquire().await;
+		let span = tracing::debug_span!(
+			"batch_request",
+			batch_len = tracing::field::Empty,
+			methods = tracing::field::Empty,
+			size = tracing::field::Empty
+		);
+		async move {
+			let mut calls = Vec::new();
+			// NOTE(niklasad1): If more than `u64::MAX` requests are performed in the `batch` then duplicate IDs are used
+			// which we don't support because ID is used to uniquely identify a given request.
+			let mut ids = HashSet::new();
 
-		let batch_request = jsonrpc::Request::Batch(calls);
-		let response = self
-			.transport
-			.send_request_and_wait_for_response(batch_request)
-			.await
-			.map_err(|e| Error::TransportError(Box::new(e)))?;
+			for (method, params) in requests.into_iter() {
+				let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+				calls.push(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
+					jsonrpc: jsonrpc::Version::V2,
+					method: method.into(),
+					params: params.into(),
+					id: jsonrpc::Id::Num(id),
+				}));
+				ids.insert(id);
+			}
+			let methods = calls
+				.iter()
+				.map(|c| match c {
+					jsonrpc::Call::MethodCall(mc) => mc.method.as_str(),
+					_ => "",
+				})
+				.collect::<Vec<_>>()
+				.join(",");
+			tracing::Span::current().record("batch_len", &calls.len()).record("methods", &methods.as_str());
+
+			let batch_request = jsonrpc::Request::Batch(calls);
+			let size = self.log_body("send", &batch_request);
+			tracing::Span::current().record("size", &size);
+			let response = self.next().run(batch_request).await?;
+			self.log_body("recv", &response);
 
-		match response {
-			jsonrpc::Response::Single(_) => {
-				Err(Error::Custom("Server replied with single response to a batch request".to_string()))
+			match response {
+				jsonrpc::Response::Single(_) => {
+					Err(Error::Custom("Server replied with single response to a batch request".to_string()))
+				}
+				jsonrpc::Response::Notif(_notif) => {
+					Err(Error::Custom("Server replied with notification to a a batch request".to_string()))
+				}
+				jsonrpc::Response::Batch(rps) => {
+					let mut responses = Vec::with_capacity(ids.len());
+					for rp in rps {
+						let id = match rp.id().as_number() {
+							Some(n) => *n,
+							_ => return Err(Error::InvalidRequestId),
+						};
+						if !ids.remove(&id) {
+							return Err(Error::InvalidRequestId);
+						}
+						let val: JsonValue = rp.try_into().map_err(Error::Request)?;
+						responses.push(val);
+					}
+					Ok(responses)
+				}
 			}
-			jsonrpc::Response::Notif(_notif) => {
-				Err(Error::Custom("Server replied with notification to a a batch request".to_string()))
+		}
+		.instrument(span)
+		.await
+	}
+
+	/// Perform a batch request towards the server, tolerating individual calls that fail.
+	///
+	/// Unlike [`batch_request`](HttpClient::batch_request), this does not fail the whole batch
+	/// when a single call errors. Instead it returns one `Result` per submitted call, in the same
+	/// order the calls were submitted, regardless of the order the server replied in.
+	pub async fn batch_request_with_results<'a>(
+		&self,
+		requests: impl IntoIterator<Item = (impl Into<String>, impl Into<jsonrpc::Params>)>,
+	) -> Result<Vec<Result<JsonValue, Error>>, Error> {
+		let _permit = self.request_limit.acquire().await;
+		let span = tracing::debug_span!(
+			"batch_request_with_results",
+			batch_len = tracing::field::Empty,
+			methods = tracing::field::Empty,
+			size = tracing::field::Empty
+		);
+		async move {
+			let mut calls = Vec::new();
+			// Maps a request ID back to its position in the submitted batch, so that a response can
+			// be placed into the right slot even if the server replies out of order.
+			let mut id_to_pos = std::collections::HashMap::new();
+
+			for (pos, (method, params)) in requests.into_iter().enumerate() {
+				let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+				calls.push(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
+					jsonrpc: jsonrpc::Version::V2,
+					method: method.into(),
+					params: params.into(),
+					id: jsonrpc::Id::Num(id),
+				}));
+				id_to_pos.insert(id, pos);
 			}
-			jsonrpc::Response::Batch(rps) => {
-				let mut responses = Vec::with_capacity(ids.len());
-				for rp in rps {
-					let id = match rp.id().as_number() {
-						Some(n) => *n,
-						_ => return Err(Error::InvalidRequestId),
-					};
-					if !ids.remove(&id) {
-						return Err(Error::InvalidRequestId);
-					}
-					let val: JsonValue = rp.try_into().map_err(Error::Request)?;
-					responses.push(val);
+			let ids: HashSet<u64> = id_to_pos.keys().copied().collect();
+			let len = calls.len();
+			let methods = calls
+				.iter()
+				.map(|c| match c {
+					jsonrpc::Call::MethodCall(mc) => mc.method.as_str(),
+					_ => "",
+				})
+				.collect::<Vec<_>>()
+				.join(",");
+			tracing::Span::current().record("batch_len", &len).record("methods", &methods.as_str());
+
+			let batch_request = jsonrpc::Request::Batch(calls);
+			let size = self.log_body("send", &batch_request);
+			tracing::Span::current().record("size", &size);
+			let response = self.next().run(batch_request).await?;
+			self.log_body("recv", &response);
+
+			match response {
+				jsonrpc::Response::Single(_) => {
+					Err(Error::Custom("Server replied with single response to a batch request".to_string()))
+				}
+				jsonrpc::Response::Notif(_notif) => {
+					Err(Error::Custom("Server replied with notification to a a batch request".to_string()))
 				}
-				Ok(responses)
+				jsonrpc::Response::Batch(rps) => reassemble_batch_results(rps, ids, &id_to_pos, len),
 			}
 		}
+		.instrument(span)
+		.await
 	}
 
 	fn process_response(response: jsonrpc::Output, expected_id: u64) -> Result<JsonValue, Error> {
@@ -159,3 +358,155 @@ impl HttpClient {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	/// A middleware layer that only records that it ran, then forwards to the rest of the stack.
+	struct RecordingLayer {
+		name: &'static str,
+		calls: Arc<Mutex<Vec<String>>>,
+	}
+
+	#[async_trait::async_trait]
+	impl RpcClientServiceT for RecordingLayer {
+		async fn call(&self, request: jsonrpc::Request, next: Next<'_>) -> Result<jsonrpc::Response, Error> {
+			self.calls.lock().unwrap().push(format!("{}::call", self.name));
+			next.run(request).await
+		}
+
+		async fn call_notification(&self, request: jsonrpc::Request, next: NotificationNext<'_>) -> Result<(), Error> {
+			self.calls.lock().unwrap().push(format!("{}::call_notification", self.name));
+			next.run(request).await
+		}
+	}
+
+	#[tokio::test]
+	async fn middleware_layers_run_in_order_for_request_batch_and_notification() {
+		// The transport itself never comes up, so every call below returns a `TransportError`;
+		// what this test cares about is that both layers still ran, in installation order.
+		let calls = Arc::new(Mutex::new(Vec::new()));
+		let layer_a = Arc::new(RecordingLayer { name: "a", calls: calls.clone() });
+		let layer_b = Arc::new(RecordingLayer { name: "b", calls: calls.clone() });
+		let client = HttpClient::new("http://127.0.0.1:0", HttpConfig::default())
+			.unwrap()
+			.with_middleware(vec![layer_a, layer_b]);
+
+		let _ = client.request("method", jsonrpc::Params::None).await;
+		let _ = client.batch_request(vec![("method", jsonrpc::Params::None)]).await;
+		let _ = client.notification("method", jsonrpc::Params::None).await;
+
+		assert_eq!(
+			*calls.lock().unwrap(),
+			vec![
+				"a::call",
+				"b::call",
+				"a::call",
+				"b::call",
+				"a::call_notification",
+				"b::call_notification",
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn try_request_fails_when_the_concurrency_limit_is_exhausted() {
+		let config = HttpConfig { max_concurrent_requests: 1, ..HttpConfig::default() };
+		let client = HttpClient::new("http://127.0.0.1:0", config).unwrap();
+
+		// Hold the only available slot.
+		let _permit = client.request_limit.try_acquire().unwrap();
+
+		assert!(matches!(
+			client.try_request("method", jsonrpc::Params::None).await,
+			Err(Error::Custom(_))
+		));
+
+		// A blocking `request` waits for the slot to free up instead of failing immediately, so it
+		// should still be pending once the held permit has had time to (not) release.
+		let waited = tokio::time::timeout(
+			std::time::Duration::from_millis(50),
+			client.request("method", jsonrpc::Params::None),
+		)
+		.await;
+		assert!(waited.is_err(), "request() should still be waiting for a free slot");
+	}
+
+	fn client_with_log_body_len(max_log_body_len: usize) -> HttpClient {
+		let config = HttpConfig { max_log_body_len, ..HttpConfig::default() };
+		HttpClient::new("http://127.0.0.1:0", config).unwrap()
+	}
+
+	/// Captures the fields of the single `trace!` event emitted by `log_body`.
+	#[derive(Default, Clone)]
+	struct CapturedFields(Arc<Mutex<std::collections::HashMap<String, String>>>);
+
+	impl tracing::field::Visit for CapturedFields {
+		fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+			self.0.lock().unwrap().insert(field.name().to_string(), format!("{:?}", value));
+		}
+	}
+
+	impl tracing::Subscriber for CapturedFields {
+		fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+			true
+		}
+		fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+			tracing::span::Id::from_u64(1)
+		}
+		fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+		fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+		fn event(&self, event: &tracing::Event<'_>) {
+			event.record(&mut self.clone());
+		}
+		fn enter(&self, _: &tracing::span::Id) {}
+		fn exit(&self, _: &tracing::span::Id) {}
+	}
+
+	fn capture_log_body_fields(client: &HttpClient, body: &impl serde::Serialize) -> (usize, std::collections::HashMap<String, String>) {
+		let captured = CapturedFields::default();
+		let size = tracing::subscriber::with_default(captured.clone(), || client.log_body("send", body));
+		let fields = captured.0.lock().unwrap().clone();
+		(size, fields)
+	}
+
+	#[test]
+	fn log_body_reports_the_byte_size_not_the_char_count() {
+		// Each "é" is one `char` but two UTF-8 bytes, so byte size and char count diverge once the
+		// body is serialized (serde_json wraps it in quotes, but that's ASCII and doesn't change
+		// the gap between byte and char counts).
+		let client = client_with_log_body_len(100);
+		let body = "é".repeat(10);
+
+		let (size, fields) = capture_log_body_fields(&client, &body);
+
+		let serialized = serde_json::to_string(&body).unwrap();
+		assert_eq!(size, serialized.len());
+		assert_ne!(size, serialized.chars().count());
+		assert!(!fields.contains_key("truncated"));
+	}
+
+	#[test]
+	fn log_body_does_not_truncate_when_within_the_limit() {
+		let client = client_with_log_body_len(10);
+		// Serializing adds two quotes, so this body is exactly 10 chars once logged.
+		let body = "a".repeat(8);
+
+		let (_, fields) = capture_log_body_fields(&client, &body);
+
+		assert!(!fields.contains_key("truncated"));
+	}
+
+	#[test]
+	fn log_body_truncates_to_exactly_max_log_body_len_chars_once_over_the_limit() {
+		let client = client_with_log_body_len(10);
+		let body = "a".repeat(11);
+
+		let (_, fields) = capture_log_body_fields(&client, &body);
+
+		assert_eq!(fields.get("truncated").map(String::as_str), Some("true"));
+		assert_eq!(fields.get("body").unwrap().chars().count(), 10);
+	}
+}
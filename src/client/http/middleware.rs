@@ -0,0 +1,68 @@
+use crate::client::http::transport::HttpTransportClient;
+use crate::types::client::Error;
+use crate::types::jsonrpc;
+use std::sync::Arc;
+
+/// A middleware layer around the dispatch performed by [`HttpClient`](crate::client::http::client::HttpClient).
+///
+/// Implementations forward the request to the rest of the stack via [`Next::run`] (or
+/// [`NotificationNext::run`] for notifications), observing or rewriting it on the way.
+#[async_trait::async_trait]
+pub trait RpcClientServiceT: Send + Sync {
+	/// Handle an outgoing request (a single call or a batch), forwarding it to `next` to continue
+	/// the stack.
+	async fn call(&self, request: jsonrpc::Request, next: Next<'_>) -> Result<jsonrpc::Response, Error>;
+
+	/// Handle an outgoing notification, forwarding it to `next` to continue the stack.
+	///
+	/// The default implementation passes the notification straight through, so a layer only
+	/// interested in request/response traffic does not need to override this.
+	async fn call_notification(&self, request: jsonrpc::Request, next: NotificationNext<'_>) -> Result<(), Error> {
+		next.run(request).await
+	}
+}
+
+/// The remainder of the middleware stack, to be invoked by a [`RpcClientServiceT`] implementation.
+///
+/// Calling [`run`](Next::run) either hands the request to the next layer, or, once every layer
+/// has been invoked, sends it over the underlying transport.
+pub struct Next<'a> {
+	pub(crate) transport: &'a HttpTransportClient,
+	pub(crate) remaining: &'a [Arc<dyn RpcClientServiceT>],
+}
+
+impl<'a> Next<'a> {
+	/// Continues the stack with the given request.
+	pub async fn run(self, request: jsonrpc::Request) -> Result<jsonrpc::Response, Error> {
+		match self.remaining.split_first() {
+			Some((layer, rest)) => layer.call(request, Next { transport: self.transport, remaining: rest }).await,
+			None => self
+				.transport
+				.send_request_and_wait_for_response(request)
+				.await
+				.map_err(|e| Error::TransportError(Box::new(e))),
+		}
+	}
+}
+
+/// The notification-dispatch counterpart of [`Next`], to be invoked by a
+/// [`RpcClientServiceT::call_notification`] implementation.
+///
+/// Calling [`run`](NotificationNext::run) either hands the notification to the next layer, or,
+/// once every layer has been invoked, sends it over the underlying transport.
+pub struct NotificationNext<'a> {
+	pub(crate) transport: &'a HttpTransportClient,
+	pub(crate) remaining: &'a [Arc<dyn RpcClientServiceT>],
+}
+
+impl<'a> NotificationNext<'a> {
+	/// Continues the stack with the given notification.
+	pub async fn run(self, request: jsonrpc::Request) -> Result<(), Error> {
+		match self.remaining.split_first() {
+			Some((layer, rest)) => {
+				layer.call_notification(request, NotificationNext { transport: self.transport, remaining: rest }).await
+			}
+			None => self.transport.send_notification(request).await.map_err(|e| Error::TransportError(Box::new(e))),
+		}
+	}
+}
@@ -0,0 +1,3 @@
+pub mod client;
+pub mod middleware;
+pub mod transport;
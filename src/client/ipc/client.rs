@@ -0,0 +1,207 @@
+use crate::client::batch::reassemble_batch_results;
+use crate::client::ipc::transport::IpcTransportClient;
+use crate::types::client::Error;
+use crate::types::jsonrpc::{self, JsonValue};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+
+/// JSON-RPC IPC Client that provides functionality to perform method calls and notifications
+/// over a Unix domain socket (or, on Windows, a named pipe), instead of HTTP.
+///
+/// This is useful for daemon/node processes that want to expose a local JSON-RPC control
+/// endpoint without binding a TCP port.
+///
+/// WARNING: The async methods must be executed on [Tokio 0.2](https://docs.rs/tokio/0.2.22/tokio).
+pub struct IpcClient {
+	/// IPC transport client, guarded by a lock since a single socket connection can only be
+	/// driven by one in-flight call at a time.
+	transport: Mutex<IpcTransportClient>,
+	/// Request ID that wraps around when overflowing.
+	request_id: AtomicU64,
+}
+
+impl IpcClient {
+	/// Connects to a local Unix domain socket (or, on Windows, a named pipe) at the given path.
+	///
+	/// Fails when the socket cannot be opened.
+	pub async fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+		let transport =
+			IpcTransportClient::new(path).await.map_err(|e| Error::TransportError(Box::new(e)))?;
+		Ok(Self { transport: Mutex::new(transport), request_id: AtomicU64::new(0) })
+	}
+
+	/// Send a notification to the server.
+	///
+	/// WARNING: This method must be executed on [Tokio 0.2](https://docs.rs/tokio/0.2.22/tokio).
+	pub async fn notification(
+		&self,
+		method: impl Into<String>,
+		params: impl Into<jsonrpc::Params>,
+	) -> Result<(), Error> {
+		let request = jsonrpc::Request::Single(jsonrpc::Call::Notification(jsonrpc::Notification {
+			jsonrpc: jsonrpc::Version::V2,
+			method: method.into(),
+			params: params.into(),
+		}));
+
+		self.transport
+			.lock()
+			.await
+			.send_notification(request)
+			.await
+			.map_err(|e| Error::TransportError(Box::new(e)))
+	}
+
+	/// Perform a request towards the server.
+	///
+	/// WARNING: This method must be executed on [Tokio 0.2](https://docs.rs/tokio/0.2.22/tokio).
+	pub async fn request(
+		&self,
+		method: impl Into<String>,
+		params: impl Into<jsonrpc::Params>,
+	) -> Result<JsonValue, Error> {
+		// NOTE: `fetch_add` wraps on overflow which is intended.
+		let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+		let request = jsonrpc::Request::Single(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
+			jsonrpc: jsonrpc::Version::V2,
+			method: method.into(),
+			params: params.into(),
+			id: jsonrpc::Id::Num(id),
+		}));
+
+		let response = self
+			.transport
+			.lock()
+			.await
+			.send_request_and_wait_for_response(request)
+			.await
+			.map_err(|e| Error::TransportError(Box::new(e)))?;
+
+		match response {
+			jsonrpc::Response::Single(rp) => Self::process_response(rp, id),
+			// Server should not send batch response to a single request.
+			jsonrpc::Response::Batch(_rps) => {
+				Err(Error::Custom("Server replied with batch response to a single request".to_string()))
+			}
+			// Server should not reply to a Notification.
+			jsonrpc::Response::Notif(_notif) => {
+				Err(Error::Custom(format!("Server replied with notification response to request ID: {}", id)))
+			}
+		}
+	}
+
+	/// Perform a batch request towards the server.
+	///
+	/// Returns `Ok` if all requests were answered successfully.
+	/// Returns `Error` if any of the requests fails.
+	pub async fn batch_request<'a>(
+		&self,
+		requests: impl IntoIterator<Item = (impl Into<String>, impl Into<jsonrpc::Params>)>,
+	) -> Result<Vec<JsonValue>, Error> {
+		let mut calls = Vec::new();
+		// NOTE: If more than `u64::MAX` requests are performed in the `batch` then duplicate IDs are used
+		// which we don't support because ID is used to uniquely identify a given request.
+		let mut ids = HashSet::new();
+
+		for (method, params) in requests.into_iter() {
+			let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+			calls.push(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
+				jsonrpc: jsonrpc::Version::V2,
+				method: method.into(),
+				params: params.into(),
+				id: jsonrpc::Id::Num(id),
+			}));
+			ids.insert(id);
+		}
+
+		let batch_request = jsonrpc::Request::Batch(calls);
+		let response = self
+			.transport
+			.lock()
+			.await
+			.send_request_and_wait_for_response(batch_request)
+			.await
+			.map_err(|e| Error::TransportError(Box::new(e)))?;
+
+		match response {
+			jsonrpc::Response::Single(_) => {
+				Err(Error::Custom("Server replied with single response to a batch request".to_string()))
+			}
+			jsonrpc::Response::Notif(_notif) => {
+				Err(Error::Custom("Server replied with notification to a a batch request".to_string()))
+			}
+			jsonrpc::Response::Batch(rps) => {
+				let mut responses = Vec::with_capacity(ids.len());
+				for rp in rps {
+					let id = match rp.id().as_number() {
+						Some(n) => *n,
+						_ => return Err(Error::InvalidRequestId),
+					};
+					if !ids.remove(&id) {
+						return Err(Error::InvalidRequestId);
+					}
+					let val: JsonValue = rp.try_into().map_err(Error::Request)?;
+					responses.push(val);
+				}
+				Ok(responses)
+			}
+		}
+	}
+
+	/// Perform a batch request towards the server, tolerating individual calls that fail.
+	///
+	/// Unlike [`batch_request`](IpcClient::batch_request), this does not fail the whole batch
+	/// when a single call errors. Instead it returns one `Result` per submitted call, in the same
+	/// order the calls were submitted, regardless of the order the server replied in.
+	pub async fn batch_request_with_results<'a>(
+		&self,
+		requests: impl IntoIterator<Item = (impl Into<String>, impl Into<jsonrpc::Params>)>,
+	) -> Result<Vec<Result<JsonValue, Error>>, Error> {
+		let mut calls = Vec::new();
+		// Maps a request ID back to its position in the submitted batch, so that a response can
+		// be placed into the right slot even if the server replies out of order.
+		let mut id_to_pos = std::collections::HashMap::new();
+
+		for (pos, (method, params)) in requests.into_iter().enumerate() {
+			let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+			calls.push(jsonrpc::Call::MethodCall(jsonrpc::MethodCall {
+				jsonrpc: jsonrpc::Version::V2,
+				method: method.into(),
+				params: params.into(),
+				id: jsonrpc::Id::Num(id),
+			}));
+			id_to_pos.insert(id, pos);
+		}
+		let ids: HashSet<u64> = id_to_pos.keys().copied().collect();
+		let len = calls.len();
+
+		let batch_request = jsonrpc::Request::Batch(calls);
+		let response = self
+			.transport
+			.lock()
+			.await
+			.send_request_and_wait_for_response(batch_request)
+			.await
+			.map_err(|e| Error::TransportError(Box::new(e)))?;
+
+		match response {
+			jsonrpc::Response::Single(_) => {
+				Err(Error::Custom("Server replied with single response to a batch request".to_string()))
+			}
+			jsonrpc::Response::Notif(_notif) => {
+				Err(Error::Custom("Server replied with notification to a a batch request".to_string()))
+			}
+			jsonrpc::Response::Batch(rps) => reassemble_batch_results(rps, ids, &id_to_pos, len),
+		}
+	}
+
+	fn process_response(response: jsonrpc::Output, expected_id: u64) -> Result<JsonValue, Error> {
+		match response.id().as_number() {
+			Some(n) if n == &expected_id => response.try_into().map_err(Error::Request),
+			_ => Err(Error::InvalidRequestId),
+		}
+	}
+}
@@ -0,0 +1,5 @@
+mod client;
+mod transport;
+
+pub use client::IpcClient;
+pub use transport::{IpcTransportClient, IpcTransportError};
@@ -0,0 +1,158 @@
+use crate::types::jsonrpc;
+use std::io;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[cfg(unix)]
+use tokio::net::UnixStream as IpcStream;
+#[cfg(windows)]
+use parity_tokio_ipc::Endpoint as IpcEndpoint;
+#[cfg(windows)]
+type IpcStream = parity_tokio_ipc::Connection;
+
+/// Sending end of an IPC connection split off by [`IpcTransportClient`].
+type IpcSender = Box<dyn AsyncWrite + Send + Sync + Unpin>;
+/// Receiving end of an IPC connection split off by [`IpcTransportClient`].
+type IpcReceiver = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+/// Error that can occur when sending or receiving JSON-RPC messages over an IPC socket.
+#[derive(Debug, thiserror::Error)]
+pub enum IpcTransportError {
+	/// Error when opening or reading/writing to the socket.
+	#[error("IO error: {0}")]
+	Io(#[from] io::Error),
+	/// Failed to parse a received frame as a JSON-RPC response.
+	#[error("Parse error: {0}")]
+	Parse(#[from] serde_json::Error),
+	/// The connection was closed by the remote side while a response was still pending.
+	#[error("The remote side closed the connection")]
+	ConnectionClosed,
+}
+
+/// JSON-RPC transport client that speaks newline-delimited JSON-RPC over a Unix domain socket
+/// (or a Windows named pipe), instead of HTTP.
+///
+/// Each outgoing [`jsonrpc::Request`] is written as a single JSON document followed by a line
+/// feed, and incoming bytes are buffered and split into individual JSON documents as they arrive,
+/// so that a response does not need to be read in a single `read` call.
+pub struct IpcTransportClient {
+	writer: IpcSender,
+	reader: IpcReceiver,
+	/// Bytes read from the socket that have not yet formed a complete JSON document.
+	buffer: Vec<u8>,
+}
+
+impl IpcTransportClient {
+	/// Connects to a Unix domain socket at the given path.
+	#[cfg(unix)]
+	pub async fn new(path: impl AsRef<Path>) -> Result<Self, IpcTransportError> {
+		let stream = IpcStream::connect(path.as_ref()).await?;
+		let (reader, writer) = tokio::io::split(stream);
+		Ok(Self { reader: Box::new(reader), writer: Box::new(writer), buffer: Vec::new() })
+	}
+
+	/// Connects to a Windows named pipe at the given path (e.g. `\\.\pipe\my-daemon`).
+	#[cfg(windows)]
+	pub async fn new(path: impl AsRef<Path>) -> Result<Self, IpcTransportError> {
+		let stream = IpcEndpoint::connect(path.as_ref()).await?;
+		let (reader, writer) = tokio::io::split(stream);
+		Ok(Self { reader: Box::new(reader), writer: Box::new(writer), buffer: Vec::new() })
+	}
+
+	/// Sends a notification, without waiting for a response.
+	pub async fn send_notification(&mut self, request: jsonrpc::Request) -> Result<(), IpcTransportError> {
+		self.send(&request).await
+	}
+
+	/// Sends a request and waits for the server to return a full JSON-RPC response frame.
+	pub async fn send_request_and_wait_for_response(
+		&mut self,
+		request: jsonrpc::Request,
+	) -> Result<jsonrpc::Response, IpcTransportError> {
+		self.send(&request).await?;
+		self.next_response().await
+	}
+
+	async fn send(&mut self, request: &jsonrpc::Request) -> Result<(), IpcTransportError> {
+		let mut bytes = serde_json::to_vec(request)?;
+		bytes.push(b'\n');
+		self.writer.write_all(&bytes).await?;
+		self.writer.flush().await?;
+		Ok(())
+	}
+
+	/// Reads from the socket, a chunk at a time, until `buffer` contains at least one complete
+	/// JSON document, then deserializes and removes that document from the front of the buffer.
+	async fn next_response(&mut self) -> Result<jsonrpc::Response, IpcTransportError> {
+		let mut chunk = [0u8; 4096];
+		loop {
+			if let Some((response, consumed)) = Self::try_parse_frame(&self.buffer)? {
+				self.buffer.drain(..consumed);
+				return Ok(response);
+			}
+
+			let n = self.reader.read(&mut chunk).await?;
+			if n == 0 {
+				return Err(IpcTransportError::ConnectionClosed);
+			}
+			self.buffer.extend_from_slice(&chunk[..n]);
+		}
+	}
+
+	/// Tries to split off and parse the first complete JSON document in `buffer`.
+	///
+	/// Returns the parsed response along with the number of bytes it consumed, so that any
+	/// bytes belonging to the next frame are left untouched in the caller's buffer.
+	fn try_parse_frame(buffer: &[u8]) -> Result<Option<(jsonrpc::Response, usize)>, IpcTransportError> {
+		let mut stream = serde_json::Deserializer::from_slice(buffer).into_iter::<jsonrpc::Response>();
+		match stream.next() {
+			Some(Ok(response)) => Ok(Some((response, stream.byte_offset()))),
+			Some(Err(e)) if e.is_eof() => Ok(None),
+			Some(Err(e)) => Err(e.into()),
+			None => Ok(None),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_single_response_id(response: jsonrpc::Response, expected_id: u64) {
+		match response {
+			jsonrpc::Response::Single(rp) => assert_eq!(rp.id().as_number(), Some(&expected_id)),
+			_ => panic!("expected a single response"),
+		}
+	}
+
+	#[test]
+	fn try_parse_frame_returns_none_on_a_partial_document() {
+		// A response split across two `read()` calls: only the first half has arrived so far.
+		let partial = br#"{"jsonrpc":"2.0","result":1,"id":1"#;
+		assert!(IpcTransportClient::try_parse_frame(partial).unwrap().is_none());
+	}
+
+	#[test]
+	fn try_parse_frame_parses_a_document_once_it_is_complete() {
+		let full = br#"{"jsonrpc":"2.0","result":1,"id":1}"#;
+		let (response, consumed) = IpcTransportClient::try_parse_frame(full).unwrap().unwrap();
+		assert_eq!(consumed, full.len());
+		assert_single_response_id(response, 1);
+	}
+
+	#[test]
+	fn try_parse_frame_splits_multiple_frames_received_in_a_single_read() {
+		let first = br#"{"jsonrpc":"2.0","result":1,"id":1}"#;
+		let second = br#"{"jsonrpc":"2.0","result":2,"id":2}"#;
+		let mut buffer = first.to_vec();
+		buffer.extend_from_slice(second);
+
+		let (response, consumed) = IpcTransportClient::try_parse_frame(&buffer).unwrap().unwrap();
+		assert_eq!(consumed, first.len());
+		assert_single_response_id(response, 1);
+
+		let (response, consumed) = IpcTransportClient::try_parse_frame(&buffer[consumed..]).unwrap().unwrap();
+		assert_eq!(consumed, second.len());
+		assert_single_response_id(response, 2);
+	}
+}
@@ -0,0 +1,3 @@
+pub(crate) mod batch;
+pub mod http;
+pub mod ipc;